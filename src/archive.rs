@@ -0,0 +1,100 @@
+//! Archives the bytes `clean` would otherwise destroy.
+//!
+//! Before files matched by a `clean` run are deleted, they're packed into
+//! a zstd-compressed tar archive and stored as a BLOB in the `archives`
+//! table, keyed by the entry's project path. The archive is built in
+//! memory (the whole tar and the whole compressed bytes are held at once)
+//! and then written to the database with SQLite's incremental BLOB I/O.
+//! Restoring reads the whole compressed archive out of the BLOB and
+//! decompresses it in memory before unpacking it.
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use rusqlite::{params, Connection, DatabaseName};
+
+/// Packs `paths` (each rooted under `root`) into a zstd-compressed tar
+/// archive and stores it for `entry_path`, replacing any archive already
+/// stored for that entry.
+pub fn archive_before_clean(
+    conn: &Connection,
+    entry_path: &str,
+    root: &Path,
+    paths: &[PathBuf],
+) -> rusqlite::Result<()> {
+    let packed = pack(root, paths).map_err(io_err_to_sqlite)?;
+
+    conn.execute(
+        "DELETE FROM archives WHERE entry_path = ?1",
+        params![entry_path],
+    )?;
+    conn.execute(
+        "INSERT INTO archives (entry_path, archive, created_at) VALUES (?1, zeroblob(?2), ?3)",
+        params![entry_path, packed.len() as i64, Local::now().to_string()],
+    )?;
+
+    let row_id = conn.last_insert_rowid();
+    let mut blob = conn.blob_open(DatabaseName::Main, "archives", "archive", row_id, false)?;
+    blob.write_all(&packed).map_err(io_err_to_sqlite)?;
+
+    Ok(())
+}
+
+/// Converts the I/O errors that tar/zstd/blob operations raise into a
+/// `rusqlite::Error`, so callers can propagate everything through one
+/// `Result` type the way the rest of this crate does.
+fn io_err_to_sqlite(err: std::io::Error) -> rusqlite::Error {
+    rusqlite::Error::ToSqlConversionFailure(Box::new(err))
+}
+
+fn pack(root: &Path, paths: &[PathBuf]) -> std::io::Result<Vec<u8>> {
+    let mut tar_bytes = Vec::new();
+    {
+        let mut builder = tar::Builder::new(&mut tar_bytes);
+        for path in paths {
+            let relative = path.strip_prefix(root).unwrap_or(path);
+            if path.is_dir() {
+                builder.append_dir_all(relative, path)?;
+            } else {
+                builder.append_path_with_name(path, relative)?;
+            }
+        }
+        builder.finish()?;
+    }
+
+    zstd::encode_all(tar_bytes.as_slice(), 0)
+}
+
+/// Unpacks the archive stored for `entry_path` back onto disk under
+/// `root`, restoring every file it contains to its original relative
+/// location.
+pub fn restore(conn: &Connection, entry_path: &str, root: &Path) -> rusqlite::Result<()> {
+    let row_id: i64 = conn.query_row(
+        "SELECT id FROM archives WHERE entry_path = ?1",
+        params![entry_path],
+        |row| row.get(0),
+    )?;
+
+    let mut blob = conn.blob_open(DatabaseName::Main, "archives", "archive", row_id, true)?;
+    let mut compressed = Vec::new();
+    blob.read_to_end(&mut compressed).map_err(io_err_to_sqlite)?;
+
+    unpack(&compressed, root).map_err(io_err_to_sqlite)
+}
+
+fn unpack(compressed: &[u8], root: &Path) -> std::io::Result<()> {
+    let tar_bytes = zstd::decode_all(compressed)?;
+    let mut archive = tar::Archive::new(tar_bytes.as_slice());
+    archive.unpack(root)?;
+    Ok(())
+}
+
+/// Permanently discards the archive stored for `entry_path`, if any,
+/// reclaiming the space it held in the database.
+pub fn purge(conn: &Connection, entry_path: &str) -> rusqlite::Result<usize> {
+    conn.execute(
+        "DELETE FROM archives WHERE entry_path = ?1",
+        params![entry_path],
+    )
+}
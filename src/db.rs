@@ -5,26 +5,29 @@ use std::str::FromStr;
 
 use chrono::{DateTime, Local};
 use colored::Colorize;
-use rusqlite::Connection;
-use sea_query::{ColumnDef, Expr, Iden, Order, Query, SqliteQueryBuilder, Table};
+use rusqlite::{params, CachedStatement, Connection};
+use sea_query::{Expr, Iden, Query, SqliteQueryBuilder, Table};
 use tabled::Tabled;
 
 /// Establishes a connection to the database
 /// The database name is specified in the DB_NAME constant
+///
+/// Foreign key enforcement is off by default in SQLite and is scoped to
+/// the connection rather than persisted in the database file, so it's
+/// turned on here every time a connection is opened.
 pub fn connect_to_db(path: &str) -> Result<Connection, rusqlite::Error> {
-    Connection::open(path)
+    let conn = Connection::open(path)?;
+    conn.pragma_update(None, "foreign_keys", "ON")?;
+    Ok(conn)
 }
 
+// Schema DDL now lives in the raw SQL in `MIGRATIONS`; the only columns
+// still addressed through `sea_query`'s builder are the ones `delete_entry`
+// and `delete_all` touch.
 #[derive(Iden)]
 enum Store {
     Table,
-    Id,
-    Name,
     Path,
-    ProjectName,
-    Language,
-    Preserve,
-    CreatedAt,
 }
 
 /// Represents a Database Entry
@@ -68,91 +71,278 @@ impl EntryBuilder {
     }
 }
 
-/// Prepares the Database, creates all the tables and defines the schema
+/// A single step in the schema's migration history.
+///
+/// `version` is the `PRAGMA user_version` a database reaches once this
+/// step has been applied, and `sql` is the statement that takes it there.
+struct Migration {
+    version: i64,
+    sql: &'static str,
+}
+
+/// Ordered list of every migration the schema has ever needed. Steps are
+/// append-only: never edit or remove a past entry, only add new ones with
+/// a higher `version` so existing databases pick up exactly the steps
+/// they're missing.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        sql: "CREATE TABLE IF NOT EXISTS store (
+            id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            name VARCHAR NOT NULL,
+            path VARCHAR NOT NULL,
+            project_name VARCHAR NOT NULL,
+            language VARCHAR NOT NULL,
+            preserve VARCHAR NOT NULL,
+            created_at DATETIME NOT NULL
+        )",
+    },
+    Migration {
+        version: 2,
+        sql: "CREATE TABLE IF NOT EXISTS undo_log (
+            id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            changeset BLOB NOT NULL,
+            description VARCHAR NOT NULL,
+            created_at DATETIME NOT NULL
+        )",
+    },
+    Migration {
+        version: 3,
+        sql: "CREATE TABLE IF NOT EXISTS archives (
+            id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            entry_path VARCHAR NOT NULL UNIQUE,
+            archive BLOB NOT NULL,
+            created_at DATETIME NOT NULL
+        )",
+    },
+    Migration {
+        version: 4,
+        sql: "CREATE TABLE IF NOT EXISTS projects (
+            id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            name VARCHAR NOT NULL UNIQUE
+        );
+        CREATE TABLE IF NOT EXISTS languages (
+            id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            name VARCHAR NOT NULL UNIQUE
+        );
+        INSERT OR IGNORE INTO projects (name) SELECT DISTINCT project_name FROM store;
+        INSERT OR IGNORE INTO languages (name) SELECT DISTINCT language FROM store;
+        CREATE TABLE IF NOT EXISTS store_new (
+            id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            name VARCHAR NOT NULL,
+            path VARCHAR NOT NULL,
+            project_id INTEGER NOT NULL REFERENCES projects(id),
+            language_id INTEGER NOT NULL REFERENCES languages(id),
+            preserve VARCHAR NOT NULL,
+            created_at DATETIME NOT NULL
+        );
+        INSERT INTO store_new (id, name, path, project_id, language_id, preserve, created_at)
+            SELECT store.id, store.name, store.path, projects.id, languages.id, store.preserve, store.created_at
+            FROM store
+            JOIN projects ON projects.name = store.project_name
+            JOIN languages ON languages.name = store.language;
+        DROP TABLE store;
+        ALTER TABLE store_new RENAME TO store;",
+    },
+];
+
+/// Prepares the database by running every migration newer than the
+/// database's current `PRAGMA user_version`.
+///
+/// On a fresh database `user_version` starts at 0, so every step in
+/// [`MIGRATIONS`] runs; on an existing database only the steps added
+/// since it was last opened run. All pending steps are applied inside a
+/// single transaction, and `user_version` is bumped as each succeeds, so
+/// a failed migration never leaves the schema half-upgraded.
 pub fn prep_db(conn: &Connection) -> rusqlite::Result<usize, rusqlite::Error> {
-    let query = Table::create()
-        .table(Store::Table)
-        .if_not_exists()
-        .col(
-            ColumnDef::new(Store::Id)
-                .integer()
-                .not_null()
-                .auto_increment()
-                .primary_key(),
-        )
-        .col(ColumnDef::new(Store::Name).string().not_null())
-        .col(ColumnDef::new(Store::Path).string().not_null())
-        .col(ColumnDef::new(Store::ProjectName).string().not_null())
-        .col(ColumnDef::new(Store::Language).string().not_null())
-        .col(ColumnDef::new(Store::Preserve).string().not_null())
-        .col(ColumnDef::new(Store::CreatedAt).date_time().not_null())
-        .build(SqliteQueryBuilder);
+    let current_version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
 
-    conn.execute(&query, [])
+    let tx = conn.unchecked_transaction()?;
+    let mut applied = 0;
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current_version) {
+        tx.execute_batch(migration.sql)?;
+        tx.pragma_update(None, "user_version", migration.version)?;
+        applied += 1;
+    }
+
+    tx.commit()?;
+
+    Ok(applied)
 }
 
 fn compress_vec(v: &Vec<String>) -> String {
-    v.iter().fold(String::new(), |mut acc, f| {
-        acc.push_str(f);
-        acc
-    })
+    v.join(",")
 }
 
 fn decompress_to_vec(v: String) -> Vec<String> {
     v.split(",").map(|f| (f.to_string())).collect()
 }
 
-/// Inserts an entry into the database
+/// The parameterized insert statement used by [`insert_batch`], kept as a
+/// single constant so it stays in sync with the column order of the
+/// `Store` table.
+const INSERT_SQL: &str = "INSERT INTO store \
+    (name, path, project_id, language_id, preserve, created_at) \
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+
+/// Looks up the id of a row in `table` with the given `name`, inserting
+/// it first if it doesn't already exist. Used to resolve the `projects`
+/// and `languages` foreign keys a `Store` row points to.
+fn get_or_create_id(conn: &Connection, table: &str, name: &str) -> Result<i64, rusqlite::Error> {
+    conn.execute(
+        &format!("INSERT OR IGNORE INTO {table} (name) VALUES (?1)"),
+        params![name],
+    )?;
+
+    conn.query_row(
+        &format!("SELECT id FROM {table} WHERE name = ?1"),
+        params![name],
+        |row| row.get(0),
+    )
+}
+
+/// Runs `stmt` for a single `EntryBuilder`, first deleting any existing
+/// row with the same path so the insert behaves as an upsert.
+fn insert_with_stmt(
+    conn: &Connection,
+    stmt: &mut CachedStatement,
+    eb: &EntryBuilder,
+) -> Result<Entry, rusqlite::Error> {
+    if let Ok(existing) = does_exist(conn, &eb.path) {
+        delete_entry(conn, &existing.path)?;
+        println!("Updated entry: {}", existing.path.blue());
+    }
+
+    let project_id = get_or_create_id(conn, "projects", &eb.project_name)?;
+    let language_id = get_or_create_id(conn, "languages", &eb.language)?;
+    let time_now = Local::now().to_string();
+
+    stmt.execute(params![
+        eb.name,
+        eb.path,
+        project_id,
+        language_id,
+        compress_vec(&eb.preserve),
+        time_now,
+    ])?;
+
+    does_exist(conn, &eb.path)
+}
+
+/// Inserts a batch of entries inside a single transaction, reusing one
+/// cached, parameterized statement for every row.
+///
+/// A failure partway through the batch rolls back every row inserted so
+/// far instead of leaving the database with a partial batch.
 ///
 /// # Arguments
 ///
-/// * `conn` - A reference to the database connection
-/// * `eb` - An EntryBuilder struct
+/// * `conn` - A mutable reference to the database connection
+/// * `entries` - The `EntryBuilder`s to insert
 ///
 /// # Returns
 /// A Result enum with the following variants:
 ///
-/// * `Entry` - The entry that was inserted into the database
-/// * `rusqlite::Error` - The error that was encountered while inserting into the database
-pub fn insert_into_db(conn: &Connection, eb: EntryBuilder) -> Result<Entry, rusqlite::Error> {
-    let time_now = Local::now().to_string();
+/// * `Vec<Entry>` - The entries that were inserted into the database, in order
+/// * `rusqlite::Error` - The error that aborted the batch; no rows are kept
+pub fn insert_batch(
+    conn: &mut Connection,
+    entries: Vec<EntryBuilder>,
+) -> Result<Vec<Entry>, rusqlite::Error> {
+    let tx = conn.transaction()?;
+    let mut inserted = Vec::with_capacity(entries.len());
 
-    let query = Query::insert()
-        .into_table(Store::Table)
-        .columns([
-            Store::Name,
-            Store::Path,
-            Store::ProjectName,
-            Store::Language,
-            Store::Preserve,
-            Store::CreatedAt,
-        ])
-        .values_panic([
-            eb.name.clone().into(),
-            eb.path.clone().into(),
-            eb.project_name.clone().into(),
-            eb.language.clone().into(),
-            compress_vec(&eb.preserve).clone().into(),
-            time_now.into(),
-        ])
-        .to_string(SqliteQueryBuilder);
-
-    match does_exist(conn, &eb.path) {
-        Ok(entry) => {
-            if delete_entry(conn, &eb.path).is_ok() {
-                println!("Updated entry: {}", entry.path.blue());
-            } else{
-                println!("Failed to update entry: {}", entry.path);
-                return Err(rusqlite::Error::QueryReturnedNoRows);
-            }
+    {
+        let mut stmt = tx.prepare_cached(INSERT_SQL)?;
+        for eb in &entries {
+            inserted.push(insert_with_stmt(&tx, &mut stmt, eb)?);
         }
-        Err(rusqlite::Error::QueryReturnedNoRows) => {}
-        Err(_) => {}
     }
 
-    let _ = conn.execute(&query, []);
+    tx.commit()?;
 
-    does_exist(conn, &eb.path)
+    Ok(inserted)
+}
+
+/// Converts a stored [`Entry`] back into an [`EntryBuilder`] so it can be
+/// re-inserted into another database via [`insert_batch`].
+fn entry_to_builder(entry: Entry) -> EntryBuilder {
+    EntryBuilder::new(
+        &entry.name,
+        &entry.path,
+        &entry.project_name,
+        &entry.language,
+        Some(decompress_to_vec(entry.preserve)),
+    )
+}
+
+/// Exports every entry in `conn` into a fresh database file at `path`,
+/// creating the same schema there via [`prep_db`].
+///
+/// # Arguments
+///
+/// * `conn` - The database to export from
+/// * `path` - Where the standalone export database should be written
+///
+/// # Returns
+/// The number of entries written, or the `rusqlite::Error` that aborted the export.
+pub fn export_to(conn: &Connection, path: &str) -> Result<usize, rusqlite::Error> {
+    let mut dest = connect_to_db(path)?;
+    prep_db(&dest)?;
+
+    let builders = get_all(conn)?.into_iter().map(entry_to_builder).collect::<Vec<_>>();
+    let count = builders.len();
+    insert_batch(&mut dest, builders)?;
+
+    Ok(count)
+}
+
+/// Imports every entry from the database file at `path` into `conn`.
+///
+/// Reuses [`insert_batch`]'s upsert-by-path behaviour, so an entry whose
+/// path already exists in `conn` is updated rather than duplicated.
+///
+/// # Arguments
+///
+/// * `conn` - The database to import into
+/// * `path` - The standalone database file to read entries from
+///
+/// # Returns
+/// The number of entries merged in, or the `rusqlite::Error` that aborted the import.
+pub fn import_from(conn: &mut Connection, path: &str) -> Result<usize, rusqlite::Error> {
+    let source = connect_to_db(path)?;
+    prep_db(&source)?;
+
+    let builders = get_all(&source)?.into_iter().map(entry_to_builder).collect::<Vec<_>>();
+    let count = builders.len();
+    insert_batch(conn, builders)?;
+
+    Ok(count)
+}
+
+/// The join every read path uses to hydrate a `Store` row (which only
+/// holds `project_id`/`language_id`) back into a fully-formed [`Entry`]
+/// with the project and language names the rest of the crate expects.
+const SELECT_JOIN_SQL: &str = "SELECT store.id, store.name, store.path, \
+    projects.name, languages.name, store.preserve, store.created_at \
+    FROM store \
+    JOIN projects ON projects.id = store.project_id \
+    JOIN languages ON languages.id = store.language_id";
+
+fn row_to_entry(row: &rusqlite::Row) -> rusqlite::Result<Entry> {
+    let created_at =
+        chrono::DateTime::from_str(row.get::<_, String>(6)?.as_str()).unwrap_or(Local::now());
+
+    Ok(Entry {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        path: row.get(2)?,
+        project_name: row.get(3)?,
+        language: row.get(4)?,
+        preserve: row.get(5)?,
+        created_at,
+    })
 }
 
 /// Gets all the entries from the database
@@ -167,43 +357,78 @@ pub fn insert_into_db(conn: &Connection, eb: EntryBuilder) -> Result<Entry, rusq
 /// * `Vec<Entry>` - A vector of all the entries in the database
 /// * `rusqlite::Error` - The error that was encountered while getting the entries from the database
 pub fn get_all(conn: &Connection) -> Result<Vec<Entry>, rusqlite::Error> {
-    let query = Query::select()
-        .columns([
-            Store::Id,
-            Store::Name,
-            Store::Path,
-            Store::ProjectName,
-            Store::Language,
-            Store::Preserve,
-            Store::CreatedAt,
-        ])
-        .order_by(Store::Id, Order::Desc)
-        .from(Store::Table)
-        .to_string(SqliteQueryBuilder);
+    let query = format!("{SELECT_JOIN_SQL} ORDER BY store.id DESC");
+    let mut stmt = conn.prepare(&query)?;
 
+    let entries = stmt
+        .query_map([], row_to_entry)?
+        .map(|x| x.unwrap())
+        .collect::<Vec<Entry>>();
+
+    Ok(entries)
+}
+
+/// Gets every entry tracked under a given project name, joining through
+/// the `projects` table so `clean`/`show`/`delete` can operate on an
+/// entire project at once instead of a single path.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to the database connection
+/// * `project_name` - The project to list entries for
+///
+/// # Returns
+/// A Result enum with the following variants:
+///
+/// * `Vec<Entry>` - Every entry belonging to the project
+/// * `rusqlite::Error` - The error that was encountered while querying the database
+pub fn get_by_project(conn: &Connection, project_name: &str) -> Result<Vec<Entry>, rusqlite::Error> {
+    let query = format!("{SELECT_JOIN_SQL} WHERE projects.name = ?1 ORDER BY store.id DESC");
     let mut stmt = conn.prepare(&query)?;
 
     let entries = stmt
-        .query_map([], |row| {
-            let created_at = chrono::DateTime::from_str(row.get::<_, String>(6)?.as_str())
-                .unwrap_or(Local::now());
-
-            Ok(Entry {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                path: row.get(2)?,
-                project_name: row.get(3)?,
-                language: row.get(4)?,
-                preserve: row.get(5)?,
-                created_at,
-            })
-        })?
+        .query_map(params![project_name], row_to_entry)?
         .map(|x| x.unwrap())
         .collect::<Vec<Entry>>();
 
     Ok(entries)
 }
 
+/// Gets every tracked entry, grouped under the project it belongs to.
+///
+/// Unlike [`get_by_project`], which requires already knowing the one
+/// project you want, this covers every project in the database at once -
+/// useful for an overview of everything being tracked.
+///
+/// # Arguments
+///
+/// * `conn` - A reference to the database connection
+///
+/// # Returns
+/// A Result enum with the following variants:
+///
+/// * `Vec<(String, Vec<Entry>)>` - Each project's name paired with its entries, ordered by project name
+/// * `rusqlite::Error` - The error that was encountered while querying the database
+pub fn get_grouped_by_project(conn: &Connection) -> Result<Vec<(String, Vec<Entry>)>, rusqlite::Error> {
+    let query = format!("{SELECT_JOIN_SQL} ORDER BY projects.name, store.id DESC");
+    let mut stmt = conn.prepare(&query)?;
+
+    let entries = stmt
+        .query_map([], row_to_entry)?
+        .map(|x| x.unwrap())
+        .collect::<Vec<Entry>>();
+
+    let mut grouped: Vec<(String, Vec<Entry>)> = Vec::new();
+    for entry in entries {
+        match grouped.last_mut() {
+            Some((project, group)) if *project == entry.project_name => group.push(entry),
+            _ => grouped.push((entry.project_name.clone(), vec![entry])),
+        }
+    }
+
+    Ok(grouped)
+}
+
 /// Gets an entry from the database
 /// using the path of the file
 /// essentially checking if the file exists
@@ -227,35 +452,8 @@ pub fn get_all(conn: &Connection) -> Result<Vec<Entry>, rusqlite::Error> {
 /// returned if the entry does not exist in the database
 /// Otherwise, the entry can be essentially used as a normal entry
 pub fn does_exist(conn: &Connection, path: &str) -> Result<Entry, rusqlite::Error> {
-    let query = Query::select()
-        .columns([
-            Store::Id,
-            Store::Name,
-            Store::Path,
-            Store::ProjectName,
-            Store::Language,
-            Store::Preserve,
-            Store::CreatedAt,
-        ])
-        .from(Store::Table)
-        .and_where(Expr::col(Store::Path).eq(path))
-        .limit(1)
-        .to_string(SqliteQueryBuilder);
-
-    conn.query_row(&query, [], |row| {
-        let created_at =
-            chrono::DateTime::from_str(row.get::<_, String>(6)?.as_str()).unwrap_or(Local::now());
-
-        Ok(Entry {
-            id: row.get(0)?,
-            name: row.get(1)?,
-            path: row.get(2)?,
-            project_name: row.get(3)?,
-            language: row.get(4)?,
-            preserve: row.get(5)?,
-            created_at,
-        })
-    })
+    let query = format!("{SELECT_JOIN_SQL} WHERE store.path = ?1 LIMIT 1");
+    conn.query_row(&query, params![path], row_to_entry)
 }
 
 /// Delete an entry from the database
@@ -0,0 +1,62 @@
+//! This module contains helper functions for resolving filesystem paths
+//! used by the crate, such as the location of the database file.
+
+use std::fs;
+use std::path::PathBuf;
+
+use directories::ProjectDirs;
+
+/// Returns the data directory used to store the database and other
+/// persistent files owned by the crate.
+fn data_dir() -> PathBuf {
+    ProjectDirs::from("com", "noobscience", "garman")
+        .map(|dirs| dirs.data_dir().to_path_buf())
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Ensures the data directory used by the crate exists on disk,
+/// creating it (and any missing parents) if necessary.
+pub fn check_paths_exist() {
+    let dir = data_dir();
+    if !dir.exists() {
+        fs::create_dir_all(&dir).expect("Unable to create data directory");
+    }
+}
+
+/// Resolves a file name (e.g. `"store.db"`) to its full path inside
+/// the crate's data directory.
+pub fn get_path(name: &str) -> PathBuf {
+    data_dir().join(name)
+}
+
+/// Looks for `name` (e.g. `"store.db"`), first directly and then inside
+/// a `data/` subfolder, starting at the current working directory and
+/// ascending through its parents until one is found.
+///
+/// This lets a project keep its own database alongside its code instead
+/// of always sharing the crate's global one. If nothing is found all the
+/// way up to the filesystem root, falls back to [`get_path`].
+pub fn locate_db(name: &str) -> PathBuf {
+    if let Ok(cwd) = std::env::current_dir() {
+        let mut dir = cwd.as_path();
+
+        loop {
+            let direct = dir.join(name);
+            if direct.exists() {
+                return direct;
+            }
+
+            let nested = dir.join("data").join(name);
+            if nested.exists() {
+                return nested;
+            }
+
+            match dir.parent() {
+                Some(parent) => dir = parent,
+                None => break,
+            }
+        }
+    }
+
+    get_path(name)
+}
@@ -0,0 +1,194 @@
+//! This module implements higher-level operations that operate on a
+//! `Store` entry's project directory, such as cleaning generated artifacts.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use colored::Colorize;
+use glob::Pattern;
+use rusqlite::Connection;
+
+use crate::archive;
+use crate::db::Entry;
+
+/// Default artifact directories/patterns to remove for a given language.
+/// These are unioned with any user supplied `--patterns` before cleaning.
+pub fn default_patterns_for(language: &str) -> Vec<String> {
+    match language.to_lowercase().as_str() {
+        "rust" => vec!["target".to_string()],
+        "node" | "javascript" | "typescript" => {
+            vec!["node_modules".to_string(), "dist".to_string()]
+        }
+        "python" => vec![
+            "__pycache__".to_string(),
+            "*.pyc".to_string(),
+            ".venv".to_string(),
+        ],
+        _ => vec![],
+    }
+}
+
+/// Summary of a single `clean_entry` run: how much was reclaimed and how
+/// many files/directories were removed.
+#[derive(Debug, Default)]
+pub struct CleanReport {
+    pub bytes_reclaimed: u64,
+    pub files_removed: u64,
+}
+
+/// Cleans the project directory backing `entry`, removing any file or
+/// directory whose name matches one of `patterns`, while skipping
+/// anything matched by the entry's `preserve` globs.
+///
+/// Before anything is deleted, every matched path is archived via
+/// [`archive::archive_before_clean`] so the run can be undone with the
+/// `restore` command.
+pub fn clean_entry(conn: &Connection, entry: &Entry, patterns: &[String]) -> std::io::Result<CleanReport> {
+    let root = PathBuf::from(&entry.path);
+    let preserve_patterns: Vec<Pattern> = entry
+        .preserve
+        .split(',')
+        .filter(|p| !p.is_empty())
+        .filter_map(|p| Pattern::new(p).ok())
+        .collect();
+
+    let mut matches = Vec::new();
+    collect_matches(&root, &root, patterns, &preserve_patterns, &mut matches)?;
+
+    if matches.is_empty() {
+        return Ok(CleanReport::default());
+    }
+
+    archive::archive_before_clean(conn, &entry.path, &root, &matches)
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+    let mut report = CleanReport::default();
+    for path in &matches {
+        let metadata = fs::metadata(path)?;
+        let reclaimed = if metadata.is_dir() {
+            let size = dir_size(path)?;
+            fs::remove_dir_all(path)?;
+            size
+        } else {
+            let size = metadata.len();
+            fs::remove_file(path)?;
+            size
+        };
+
+        println!("Removed: {}", path.to_string_lossy().red());
+        report.bytes_reclaimed += reclaimed;
+        report.files_removed += 1;
+    }
+
+    Ok(report)
+}
+
+fn is_preserved(path: &Path, root: &Path, preserve_patterns: &[Pattern]) -> bool {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    preserve_patterns
+        .iter()
+        .any(|pattern| pattern.matches_path(relative) || pattern.matches_path(path))
+}
+
+fn matches_target(path: &Path, patterns: &[String]) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    patterns
+        .iter()
+        .any(|pattern| Pattern::new(pattern).map(|p| p.matches(name)).unwrap_or(false))
+}
+
+fn dir_size(path: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Walks `dir` and records every path matching `patterns` into `matches`,
+/// without descending into a directory that itself matched. Nothing is
+/// deleted here; that happens only once the caller has archived the full
+/// set of matches.
+fn collect_matches(
+    dir: &Path,
+    root: &Path,
+    patterns: &[String],
+    preserve_patterns: &[Pattern],
+    matches: &mut Vec<PathBuf>,
+) -> std::io::Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if is_preserved(&path, root, preserve_patterns) {
+            continue;
+        }
+
+        let metadata = entry.metadata()?;
+
+        if matches_target(&path, patterns) {
+            matches.push(path);
+        } else if metadata.is_dir() {
+            collect_matches(&path, root, patterns, preserve_patterns, matches)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{connect_to_db, insert_batch, prep_db, EntryBuilder};
+
+    /// Creates an empty, unique scratch directory under the OS temp dir.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("garman_test_{}_{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn clean_entry_preserves_every_comma_separated_pattern() {
+        let root = scratch_dir("preserve");
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::create_dir_all(root.join("build")).unwrap();
+        fs::write(root.join("target/artifact.bin"), b"junk").unwrap();
+        fs::write(root.join("build/artifact.bin"), b"junk").unwrap();
+
+        let mut conn = connect_to_db(":memory:").unwrap();
+        prep_db(&conn).unwrap();
+
+        let eb = EntryBuilder::new(
+            "preserve-test",
+            root.to_str().unwrap(),
+            "preserve-test",
+            "rust",
+            Some(vec!["target".to_string(), "build".to_string()]),
+        );
+        let entry = insert_batch(&mut conn, vec![eb]).unwrap().remove(0);
+
+        // "target" is also garman's own default pattern for Rust projects,
+        // so this exercises exactly the preserve-vs-default conflict the
+        // `clean` command has to resolve correctly.
+        let patterns = default_patterns_for(&entry.language);
+        let report = clean_entry(&conn, &entry, &patterns).unwrap();
+
+        assert_eq!(report.files_removed, 0);
+        assert!(root.join("target/artifact.bin").exists());
+        assert!(root.join("build/artifact.bin").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
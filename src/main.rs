@@ -5,9 +5,11 @@ use colored::Colorize;
 use db::{Entry, EntryBuilder};
 use tabled::{settings::Style, Table};
 
+mod archive;
 mod db;
 mod file;
 mod handler;
+mod undo;
 
 #[derive(Parser, Debug, Clone)]
 #[command(name="garman", author="Ishan Joshi <noobscience@duck.com>", version, about="Gargae Man for all your programs", long_about = None)]
@@ -31,7 +33,9 @@ struct Args {
     lang: Option<String>,
 }
 
-const COMMANDS: [&str; 5] = ["add", "clean", "list", "show", "delete"];
+const COMMANDS: [&str; 10] = [
+    "add", "clean", "list", "show", "delete", "export", "import", "undo", "restore", "purge",
+];
 
 fn main() {
     let args = Args::parse();
@@ -42,36 +46,87 @@ fn main() {
         return;
     }
 
+    // When a project is named and no explicit path was given, `show`,
+    // `delete` and `clean` operate on every entry tracked under that
+    // project instead of a single path.
+    let project_scope = if args.target.is_none() {
+        args.project.clone()
+    } else {
+        None
+    };
+
+    // `undo` only reverts a changeset when given its id explicitly - with
+    // no target it just lists what's recoverable, so inspecting history
+    // never has a side effect.
+    let undo_id = args
+        .target
+        .as_ref()
+        .and_then(|t| t.first())
+        .and_then(|s| s.parse::<i32>().ok());
+
+    // `export`'s destination and `import`'s source are plain filenames, not
+    // tracked project paths, and the destination in particular need not
+    // exist yet - canonicalizing them the way every other command's
+    // targets are canonicalized would required them to already exist on
+    // disk. `undo`'s target, if any, is a changeset id rather than a path
+    // at all. `restore`/`purge` targets are matched against the canonical
+    // project path an archive was stored under, so they're canonicalized
+    // when the path exists and used as given when it doesn't - the
+    // archive lookup itself reports a friendly "No archive found" error.
+    let targets_are_filenames =
+        matches!(cmd.as_str(), "export" | "import" | "undo" | "restore" | "purge");
+
     let paths = args.target.unwrap_or(vec![".".to_string()]);
-    let canon_paths = paths
-        .iter()
-        .map(|x| {
-            PathBuf::from_str(x)
-                .unwrap()
-                .canonicalize()
-                .unwrap()
-                .to_string_lossy()
-                .to_string()
-        })
-        .collect::<Vec<String>>();
+    let canon_paths = if matches!(cmd.as_str(), "restore" | "purge") {
+        paths
+            .iter()
+            .map(|x| {
+                PathBuf::from(x)
+                    .canonicalize()
+                    .map(|p| p.to_string_lossy().to_string())
+                    .unwrap_or_else(|_| x.clone())
+            })
+            .collect::<Vec<String>>()
+    } else if targets_are_filenames {
+        paths.clone()
+    } else {
+        paths
+            .iter()
+            .map(|x| {
+                PathBuf::from_str(x)
+                    .unwrap()
+                    .canonicalize()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect::<Vec<String>>()
+    };
 
     file::check_paths_exist();
 
-    let compiled_paths: Vec<(String, String, String)> = paths
-        .iter()
-        .map(|x| {
-            let path = PathBuf::from_str(x).unwrap();
-            let full_path = path.canonicalize().unwrap().to_string_lossy().to_string();
-            let path_name = path
-                .canonicalize()
-                .unwrap()
-                .file_name()
-                .unwrap()
-                .to_string_lossy()
-                .to_string();
-            (x.to_string(), full_path, path_name)
-        })
-        .collect();
+    let compiled_paths: Vec<(String, String, String)> = if targets_are_filenames {
+        paths
+            .iter()
+            .map(|x| (x.to_string(), x.to_string(), x.to_string()))
+            .collect()
+    } else {
+        paths
+            .iter()
+            .map(|x| {
+                let path = PathBuf::from_str(x).unwrap();
+                let full_path = path.canonicalize().unwrap().to_string_lossy().to_string();
+                let path_name = path
+                    .canonicalize()
+                    .unwrap()
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string();
+                (x.to_string(), full_path, path_name)
+            })
+            .collect()
+    };
     let project = args.project.unwrap_or(compiled_paths[0].2.clone());
 
     //TODO: Add lang prediction
@@ -81,35 +136,47 @@ fn main() {
         preserve = Some(p.split(",").map(|x| (x.to_string())).collect())
     }
 
-    let db_path = file::get_path("store.db").to_string_lossy().to_string();
-    let conn = db::connect_to_db(&db_path).expect("Unable to connect to DB");
+    let db_path_buf = file::locate_db("store.db");
+    let is_global = db_path_buf == file::get_path("store.db");
+    let db_path = db_path_buf.to_string_lossy().to_string();
+    println!(
+        "Using {} database: {}",
+        if is_global { "global" } else { "project-local" },
+        db_path.blue()
+    );
+
+    let mut conn = db::connect_to_db(&db_path).expect("Unable to connect to DB");
 
     db::prep_db(&conn).expect("Unable to init db");
 
     match cmd.as_str() {
         "add" => {
-            for path in compiled_paths {
-                let eb = EntryBuilder::new(
-                    &path.2,
-                    &path.1,
-                    &project.clone(),
-                    &lang.clone(),
-                    preserve.clone(),
-                );
-
-                match db::insert_into_db(&conn, eb) {
-                    Ok(entry) => {
+            let entries: Vec<EntryBuilder> = compiled_paths
+                .iter()
+                .map(|path| {
+                    EntryBuilder::new(&path.2, &path.1, &project, &lang, preserve.clone())
+                })
+                .collect();
+
+            match db::insert_batch(&mut conn, entries) {
+                Ok(inserted) => {
+                    for entry in inserted {
                         println!("Added entry: {}", entry.name.green());
                     }
-                    Err(_) => {
-                        println!("Failed to add entry: {}", path.1);
-                    }
+                }
+                Err(e) => {
+                    println!("Failed to add entries, batch rolled back: {}", e.to_string().red());
                 }
             }
         }
 
         "show" => {
-            if canon_paths.len() > 1 {
+            if let Some(ref project_name) = project_scope {
+                if let Ok(entries) = db::get_by_project(&conn, project_name) {
+                    let table = Table::new(entries).with(Style::modern_rounded()).to_string();
+                    println!("{}", table);
+                }
+            } else if canon_paths.len() > 1 {
                 println!("Constructing table");
                 if let Ok(all) = db::get_all(&conn) {
                     let filtered: Vec<Entry> = all
@@ -135,16 +202,37 @@ fn main() {
         }
 
         "list" => {
-            if let Ok(all) = db::get_all(&conn) {
-                let table = Table::new(all).with(Style::modern_rounded()).to_string();
-                println!("{}", table);
+            if let Some(ref project_name) = project_scope {
+                if let Ok(entries) = db::get_by_project(&conn, project_name) {
+                    let table = Table::new(entries).with(Style::modern_rounded()).to_string();
+                    println!("{}", table);
+                }
+            } else if let Ok(grouped) = db::get_grouped_by_project(&conn) {
+                for (project_name, entries) in grouped {
+                    println!("{}", project_name.blue());
+                    let table = Table::new(entries).with(Style::modern_rounded()).to_string();
+                    println!("{}", table);
+                }
             }
         }
 
         "delete" => {
-            for path in canon_paths {
+            let targets: Vec<String> = if let Some(ref project_name) = project_scope {
+                db::get_by_project(&conn, project_name)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|e| e.path)
+                    .collect()
+            } else {
+                canon_paths
+            };
+
+            for path in targets {
                 if let Ok(entry) = db::does_exist(&conn, &path) {
-                    if db::delete_entry(&conn, &path).is_ok() {
+                    let result = undo::with_snapshot(&conn, &format!("delete {path}"), || {
+                        db::delete_entry(&conn, &path)
+                    });
+                    if result.is_ok() {
                         println!("Deleted entry: {}", entry.path.red());
                     } else {
                         println!("Failed to delete entry: {}", entry.path);
@@ -155,8 +243,121 @@ fn main() {
             }
         }
 
+        "clean" => {
+            let entries = if let Some(ref project_name) = project_scope {
+                db::get_by_project(&conn, project_name)
+            } else {
+                db::get_all(&conn).map(|all| {
+                    all.into_iter()
+                        .filter(|e| canon_paths.contains(&e.path))
+                        .collect::<Vec<Entry>>()
+                })
+            };
+
+            if let Ok(entries) = entries {
+                let user_patterns: Vec<String> = args
+                    .patterns
+                    .map(|p| p.split(',').map(|x| x.to_string()).collect())
+                    .unwrap_or_default();
+
+                let mut total_bytes = 0u64;
+                for entry in &entries {
+                    let mut patterns = handler::default_patterns_for(&entry.language);
+                    patterns.extend(user_patterns.clone());
+
+                    let description = format!("clean {}", entry.path);
+                    let result = undo::with_snapshot(&conn, &description, || {
+                        handler::clean_entry(&conn, entry, &patterns)
+                    });
+
+                    match result {
+                        Ok(report) => {
+                            println!(
+                                "Cleaned {}: {} removed, {} reclaimed",
+                                entry.name.green(),
+                                report.files_removed,
+                                format_bytes(report.bytes_reclaimed)
+                            );
+                            total_bytes += report.bytes_reclaimed;
+                        }
+                        Err(e) => println!("Failed to clean {}: {}", entry.name.red(), e),
+                    }
+                }
+                println!("Total reclaimed: {}", format_bytes(total_bytes).green());
+            }
+        }
+
+        "export" => {
+            let out_path = paths[0].clone();
+            match db::export_to(&conn, &out_path) {
+                Ok(count) => println!("Exported {} entries to {}", count, out_path.green()),
+                Err(e) => println!("Failed to export: {}", e),
+            }
+        }
+
+        "import" => {
+            let in_path = paths[0].clone();
+            if !PathBuf::from(&in_path).is_file() {
+                println!("No such file to import: {}", in_path.red());
+            } else {
+                match db::import_from(&mut conn, &in_path) {
+                    Ok(count) => println!("Imported {} entries from {}", count, in_path.green()),
+                    Err(e) => println!("Failed to import: {}", e),
+                }
+            }
+        }
+
+        "restore" => {
+            for path in &canon_paths {
+                match archive::restore(&conn, path, std::path::Path::new(path)) {
+                    Ok(_) => println!("Restored archive for: {}", path.green()),
+                    Err(_) => println!("No archive found for: {}", path),
+                }
+            }
+        }
+
+        "purge" => {
+            for path in &canon_paths {
+                match archive::purge(&conn, path) {
+                    Ok(0) => println!("No archive found for: {}", path),
+                    Ok(_) => println!("Purged archive for: {}", path.red()),
+                    Err(e) => println!("Failed to purge archive for {}: {}", path, e),
+                }
+            }
+        }
+
+        "undo" => {
+            if let Ok(history) = undo::history(&conn) {
+                let table = Table::new(history).with(Style::modern_rounded()).to_string();
+                println!("{}", table);
+            }
+
+            match undo_id {
+                Some(id) => match undo::undo(&conn, Some(id)) {
+                    Ok(description) => println!("Undid: {}", description.green()),
+                    Err(_) => println!("Nothing to undo for id: {}", id),
+                },
+                None => println!(
+                    "Run {} to revert one of the entries above.",
+                    "garman undo <id>".blue()
+                ),
+            }
+        }
+
         _ => {
-            todo!();
+            unreachable!("every entry in COMMANDS is handled above");
         }
     }
 }
+
+/// Formats a byte count as a human readable string (KB/MB/GB)
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", size, UNITS[unit])
+}
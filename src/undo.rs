@@ -0,0 +1,139 @@
+//! Snapshot/undo support built on SQLite's session (changeset) extension.
+//!
+//! Before a mutating command (`delete`, `clean`) touches the `store`
+//! table, a [`Session`] records every change it makes there. The
+//! resulting changeset is archived in the `undo_log` table so it can
+//! later be inverted and re-applied to restore what was removed.
+//! `db::delete_all` is not run through a snapshot.
+
+use std::str::FromStr;
+
+use chrono::{DateTime, Local};
+use rusqlite::session::{ConflictAction, ConflictType, Session};
+use rusqlite::{params, Connection};
+use tabled::Tabled;
+
+/// How many changesets to keep before the oldest are discarded.
+const MAX_UNDO_HISTORY: i64 = 20;
+
+/// Runs `mutate` while recording everything it does to the `store` table,
+/// archiving the resulting changeset so the mutation can be reversed with
+/// [`undo`]. If `mutate` makes no changes (e.g. deleting a path that
+/// didn't exist, or a `clean` that doesn't touch the store table at all),
+/// nothing is recorded.
+///
+/// `mutate`'s own error type is propagated untouched; only the
+/// snapshotting machinery itself is expected to never fail.
+pub fn with_snapshot<F, T, E>(conn: &Connection, description: &str, mutate: F) -> Result<T, E>
+where
+    F: FnOnce() -> Result<T, E>,
+{
+    let mut session = Session::new(conn).expect("Unable to start undo session");
+    session
+        .attach(Some("store"))
+        .expect("Unable to attach undo session to store table");
+
+    let result = mutate()?;
+
+    if !session.is_empty() {
+        let mut changeset = Vec::new();
+        session
+            .changeset_strm(&mut changeset)
+            .expect("Unable to serialize undo changeset");
+        archive_changeset(conn, &changeset, description).expect("Unable to archive undo changeset");
+    }
+
+    Ok(result)
+}
+
+fn archive_changeset(
+    conn: &Connection,
+    changeset: &[u8],
+    description: &str,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO undo_log (changeset, description, created_at) VALUES (?1, ?2, ?3)",
+        params![changeset, description, Local::now().to_string()],
+    )?;
+
+    conn.execute(
+        "DELETE FROM undo_log WHERE id NOT IN (
+            SELECT id FROM undo_log ORDER BY id DESC LIMIT ?1
+        )",
+        params![MAX_UNDO_HISTORY],
+    )?;
+
+    Ok(())
+}
+
+/// One recoverable mutation: what it was and when it happened.
+#[derive(Debug, Tabled, Clone)]
+pub struct UndoEntry {
+    pub id: i32,
+    pub description: String,
+    pub created_at: DateTime<Local>,
+}
+
+/// Lists every changeset currently recoverable via [`undo`], newest first.
+pub fn history(conn: &Connection) -> Result<Vec<UndoEntry>, rusqlite::Error> {
+    let mut stmt =
+        conn.prepare("SELECT id, description, created_at FROM undo_log ORDER BY id DESC")?;
+
+    let entries = stmt
+        .query_map([], |row| {
+            let created_at = DateTime::from_str(row.get::<_, String>(2)?.as_str())
+                .unwrap_or(Local::now());
+            Ok(UndoEntry {
+                id: row.get(0)?,
+                description: row.get(1)?,
+                created_at,
+            })
+        })?
+        .map(|x| x.unwrap())
+        .collect::<Vec<UndoEntry>>();
+
+    Ok(entries)
+}
+
+/// Inverts a changeset so applying it reverses the original mutation.
+fn invert(changeset: &[u8]) -> Result<Vec<u8>, rusqlite::Error> {
+    let mut inverted = Vec::new();
+    rusqlite::session::invert_strm(&mut changeset.as_ref(), &mut inverted)?;
+    Ok(inverted)
+}
+
+/// Reverts the mutation recorded by changeset `id` by applying the
+/// inverse of its stored changeset, then drops that changeset from the
+/// history. Passing `None` reverts the most recently recorded mutation.
+///
+/// This is the only function in this module that actually changes the
+/// database - [`history`] is read-only, so callers must go out of their
+/// way (by picking an id, or explicitly asking for "the latest") before
+/// anything is reverted.
+///
+/// Returns the description of the mutation that was undone.
+pub fn undo(conn: &Connection, id: Option<i32>) -> Result<String, rusqlite::Error> {
+    let (id, description, changeset): (i32, String, Vec<u8>) = match id {
+        Some(id) => conn.query_row(
+            "SELECT id, description, changeset FROM undo_log WHERE id = ?1",
+            params![id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?,
+        None => conn.query_row(
+            "SELECT id, description, changeset FROM undo_log ORDER BY id DESC LIMIT 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?,
+    };
+
+    let inverted = invert(&changeset)?;
+    conn.apply_strm(
+        &mut inverted.as_slice(),
+        None::<fn(&str) -> bool>,
+        |_conflict: ConflictType, _item| ConflictAction::SQLITE_CHANGESET_REPLACE,
+    )?;
+
+    conn.execute("DELETE FROM undo_log WHERE id = ?1", params![id])?;
+
+    Ok(description)
+}